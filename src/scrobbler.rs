@@ -1,12 +1,372 @@
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::{Future, BoxFuture, Async, Poll};
 use futures::future;
-use rustfm_scrobble::{self, Scrobble};
+use rustfm_scrobble::{self, Scrobble, ScrobbleBatch};
+use rustfm_scrobble::responses::ScrobbleResponse;
+use serde_json;
+use tokio_timer::Timer;
 
-use metadata::{Track, Artist, Album, Metadata};
+use metadata::{Track, Artist, Album, Episode, Show, Metadata};
 use core::session::Session;
-use core::util::SpotifyId;
+use core::util::{SpotifyId, SpotifyAudioType};
+
+/// Last.fm accepts at most 50 scrobbles per batch submission.
+const SCROBBLE_BATCH_SIZE: usize = 50;
+
+/// Default location of the on-disk offline scrobble queue.
+const SCROBBLE_CACHE_PATH: &'static str = "scrobble_cache.json";
+
+/// Backoff applied to a throttled request when the server gives no hint.
+const RETRY_FALLBACK_BACKOFF_SECS: u64 = 5;
+
+/// Upper bound on the exponential backoff between retries.
+const RETRY_MAX_BACKOFF_SECS: u64 = 60;
+
+/// How many attempts in total a single request gets before giving up.
+const RETRY_MAX_ATTEMPTS: u32 = 8;
+
+/// Per-type capacity of the in-memory metadata cache.
+const METADATA_CACHE_CAPACITY: usize = 128;
+
+/// How many times a dropped metadata fetch is retried before the current
+/// track is abandoned, and the base delay between those attempts.
+const META_FETCH_MAX_ATTEMPTS: u32 = 5;
+const META_FETCH_BACKOFF_SECS: u64 = 5;
+
+thread_local!(static RETRY_TIMER: Timer = Timer::default());
+
+/// Seconds since the Unix epoch, clamped to 0 if the clock is before it.
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A future that completes once `dur` has elapsed, scheduled on a real timer
+/// so the task is woken when the delay is up instead of relying on the
+/// enclosing `Scrobbler` being re-polled for some unrelated reason.
+fn sleep(dur: Duration) -> BoxFuture<(), ()> {
+    RETRY_TIMER.with(|timer| timer.sleep(dur)).map_err(|_| ()).boxed()
+}
+
+fn is_auth_str(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    msg.contains("auth") || msg.contains("session") || msg.contains("key") || msg.contains("login")
+}
+
+fn is_rate_limit_str(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    msg.contains("429") || msg.contains("rate") || msg.contains("too many") || msg.contains("throttl")
+}
+
+/// Pull a `retry-after` duration out of an error message if the server
+/// supplied one (e.g. `retry-after: 12`).
+fn retry_after_str(msg: &str) -> Option<Duration> {
+    let lower = msg.to_lowercase();
+    lower.find("retry").and_then(|idx| {
+        let tail = &lower[idx..];
+        let digits: String = tail.chars()
+            .skip_while(|c| !c.is_digit(10))
+            .take_while(|c| c.is_digit(10))
+            .collect();
+        digits.parse::<u64>().ok()
+    }).and_then(|secs| if secs > 0 { Some(Duration::new(secs, 0)) } else { None })
+}
+
+/// Whether an `ignoredMessage` code from a batch response means the scrobble
+/// was stored (`0` or empty) rather than rejected by Last.fm.
+fn code_is_accepted(code: &str) -> bool {
+    let code = code.trim();
+    code.is_empty() || code == "0"
+}
+
+/// Whether Last.fm accepted an individual scrobble within a batch response.
+/// A rejected entry carries a non-zero `ignored_message` code.
+fn batch_entry_accepted(entry: &ScrobbleResponse) -> bool {
+    code_is_accepted(&entry.ignored_message.code)
+}
+
+/// Given the entries submitted in a batch and the per-entry acceptance Last.fm
+/// reported for them, return the entries that must stay queued: the ones it
+/// rejected, plus any a short response never reported on.
+fn retain_unaccepted(chunk: &[CachedScrobble], accepted: &[bool]) -> Vec<CachedScrobble> {
+    chunk.iter().enumerate()
+        .filter(|&(i, _)| accepted.get(i).cloned() != Some(true))
+        .map(|(_, cached)| cached.clone())
+        .collect()
+}
+
+/// Core Last.fm eligibility test: a track must be longer than 30 seconds and
+/// have been played for at least half its length or four minutes, whichever is
+/// smaller.
+fn track_eligible(duration: Duration, play_time: Duration) -> bool {
+    if duration <= Duration::new(30, 0) {
+        return false
+    }
+
+    let threshold = ::std::cmp::min(duration / 2, Duration::new(240, 0));
+    play_time >= threshold
+}
+
+/// Errors that carry enough information to decide whether a failed request
+/// should be retried, and how long to wait before doing so.
+pub trait RetryableError {
+    /// Whether the error represents rate limiting (HTTP 429 / Last.fm throttle)
+    /// and is therefore worth retrying after a delay.
+    fn is_rate_limited(&self) -> bool;
+
+    /// The server-provided `retry-after`, if any.
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+impl RetryableError for ScrobbleError {
+    fn is_rate_limited(&self) -> bool {
+        is_rate_limit_str(&self.msg)
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        retry_after_str(&self.msg)
+    }
+}
+
+/// A future combinator that retries its inner future on rate-limit errors,
+/// sleeping for the server-provided `retry-after` (or a fallback) and doubling
+/// the backoff on each successive failure up to a cap. Non-rate-limit errors
+/// and exhausting the attempt budget propagate unchanged.
+///
+/// The factory is re-invoked to produce a fresh inner future for each attempt,
+/// so it is reusable by `meta_fetch_future`, `now_playing_future` and
+/// `scrobble_future` alike.
+pub struct RetryBackoff<T, E, F> {
+    factory: F,
+    current: BoxFuture<T, E>,
+    attempt: u32,
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    delay: Option<BoxFuture<(), ()>>,
+}
+
+pub fn retry_backoff<T, E, F>(mut factory: F) -> BoxFuture<T, E>
+    where T: Send + 'static,
+          E: RetryableError + Send + 'static,
+          F: FnMut() -> BoxFuture<T, E> + Send + 'static {
+    let current = factory();
+    RetryBackoff {
+        factory: factory,
+        current: current,
+        attempt: 0,
+        max_attempts: RETRY_MAX_ATTEMPTS,
+        backoff: Duration::new(RETRY_FALLBACK_BACKOFF_SECS, 0),
+        max_backoff: Duration::new(RETRY_MAX_BACKOFF_SECS, 0),
+        delay: None,
+    }.boxed()
+}
+
+impl<T, E, F> Future for RetryBackoff<T, E, F>
+    where T: Send + 'static,
+          E: RetryableError + Send + 'static,
+          F: FnMut() -> BoxFuture<T, E> + Send + 'static {
+    type Item = T;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<T, E> {
+        loop {
+            // Wait out the backoff window on a real timer before the next
+            // attempt; a timer error just means we retry immediately.
+            if let Some(mut delay) = self.delay.take() {
+                match delay.poll() {
+                    Ok(Async::NotReady) => {
+                        self.delay = Some(delay);
+                        return Ok(Async::NotReady)
+                    },
+                    _ => self.current = (self.factory)(),
+                }
+            }
+
+            match self.current.poll() {
+                Ok(Async::Ready(value)) => return Ok(Async::Ready(value)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => {
+                    self.attempt += 1;
+                    if !err.is_rate_limited() || self.attempt >= self.max_attempts {
+                        return Err(err)
+                    }
+
+                    let wait = err.retry_after().unwrap_or(self.backoff);
+                    warn!("Rate limited, backing off {}s (attempt {}/{})",
+                          wait.as_secs(), self.attempt, self.max_attempts);
+                    self.delay = Some(sleep(wait));
+                    self.backoff = ::std::cmp::min(self.backoff * 2, self.max_backoff);
+                }
+            }
+        }
+    }
+}
+
+/// A small bounded least-recently-used cache. Reading or inserting a key marks
+/// it as most-recently-used; the least-recently-used key is evicted once the
+/// cache is full.
+struct LruCache<K, V> {
+    capacity: usize,
+    order: VecDeque<K>,
+    map: HashMap<K, V>,
+}
+
+impl<K: Eq + Hash + Copy, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity: capacity,
+            order: VecDeque::new(),
+            map: HashMap::new(),
+        }
+    }
+
+    fn touch(&mut self, key: K) {
+        if let Some(idx) = self.order.iter().position(|k| *k == key) {
+            self.order.remove(idx);
+        }
+        self.order.push_back(key);
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        match self.map.get(key).cloned() {
+            Some(value) => {
+                self.touch(*key);
+                Some(value)
+            },
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        self.map.insert(key, value);
+        self.touch(key);
+
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.map.remove(&evicted);
+            }
+        }
+    }
+}
+
+/// Resolved Spotify metadata cached by `SpotifyId` so replays and albums with
+/// many tracks by the same artist don't re-issue the same lookups.
+struct MetadataCache {
+    tracks: LruCache<SpotifyId, Track>,
+    artists: LruCache<SpotifyId, Artist>,
+    albums: LruCache<SpotifyId, Album>,
+}
+
+impl MetadataCache {
+    fn new(capacity: usize) -> MetadataCache {
+        MetadataCache {
+            tracks: LruCache::new(capacity),
+            artists: LruCache::new(capacity),
+            albums: LruCache::new(capacity),
+        }
+    }
+}
+
+type SharedMetadataCache = Arc<Mutex<MetadataCache>>;
+
+fn get_or_fetch_track(session: Session, cache: SharedMetadataCache, id: SpotifyId) -> BoxFuture<Track, ScrobbleError> {
+    if let Some(track) = cache.lock().unwrap().tracks.get(&id) {
+        return future::ok(track).boxed()
+    }
+
+    Track::get(&session, id)
+        .map_err(|err| ScrobbleError::new(format!("{:?}", err)))
+        .map(move |track| {
+            cache.lock().unwrap().tracks.insert(id, track.clone());
+            track
+        }).boxed()
+}
+
+fn get_or_fetch_artist(session: Session, cache: SharedMetadataCache, id: SpotifyId) -> BoxFuture<Artist, ScrobbleError> {
+    if let Some(artist) = cache.lock().unwrap().artists.get(&id) {
+        return future::ok(artist).boxed()
+    }
+
+    Artist::get(&session, id)
+        .map_err(|err| ScrobbleError::new(format!("{:?}", err)))
+        .map(move |artist| {
+            cache.lock().unwrap().artists.insert(id, artist.clone());
+            artist
+        }).boxed()
+}
+
+fn get_or_fetch_album(session: Session, cache: SharedMetadataCache, id: SpotifyId) -> BoxFuture<Album, ScrobbleError> {
+    if let Some(album) = cache.lock().unwrap().albums.get(&id) {
+        return future::ok(album).boxed()
+    }
+
+    Album::get(&session, id)
+        .map_err(|err| ScrobbleError::new(format!("{:?}", err)))
+        .map(move |album| {
+            cache.lock().unwrap().albums.insert(id, album.clone());
+            album
+        }).boxed()
+}
+
+/// Resolve metadata for `track_id` and build the corresponding `Scrobble`,
+/// returning its duration alongside it. Dispatches on the audio type so that
+/// podcast episodes are resolved via show/episode metadata rather than being
+/// treated as music tracks (which would panic for lack of artists). Kept as a
+/// free function so the retry combinator can re-invoke it for each attempt.
+fn fetch_track_meta(session: Session, cache: SharedMetadataCache, track_id: SpotifyId) -> BoxFuture<(Scrobble, Duration), ScrobbleError> {
+    match track_id.audio_type {
+        SpotifyAudioType::Podcast => fetch_episode_meta(session, track_id),
+        _ => fetch_music_meta(session, cache, track_id),
+    }
+}
+
+/// Resolve the `Track`/`Artist`/`Album` metadata for a music track. Each lookup
+/// is served from `cache` before hitting Spotify.
+fn fetch_music_meta(session: Session, cache: SharedMetadataCache, track_id: SpotifyId) -> BoxFuture<(Scrobble, Duration), ScrobbleError> {
+    let album_cache = cache.clone();
+
+    get_or_fetch_track(session.clone(), cache.clone(), track_id).and_then(move |track| {
+        let track_name = track.name.clone();
+        let duration = Duration::from_millis(track.duration as u64);
+        let artist_id = *track.artists.first().expect("No artists");
+        let album_id = track.album;
+
+        get_or_fetch_artist(session.clone(), cache, artist_id).and_then(move |artist| {
+            let artist_name = artist.name.clone();
+            get_or_fetch_album(session, album_cache, album_id)
+                .map(move |album| (track_name, artist_name, album.name.clone(), duration))
+        })
+    }).and_then(move |(track, artist, album, duration)| {
+        future::ok((Scrobble::new(&artist, &track, &album), duration))
+    }).boxed()
+}
+
+/// Resolve `Episode`/`Show` metadata for a podcast episode and map it onto a
+/// `Scrobble` using the show name as the artist and the episode title as the
+/// track, so podcast listening is reported rather than crashing the pipeline.
+fn fetch_episode_meta(session: Session, episode_id: SpotifyId) -> BoxFuture<(Scrobble, Duration), ScrobbleError> {
+    Episode::get(&session, episode_id)
+        .map_err(|err| ScrobbleError::new(format!("{:?}", err)))
+        .and_then(move |episode| {
+            let episode_name = episode.name.clone();
+            let duration = Duration::from_millis(episode.duration as u64);
+            let show_id = episode.show;
+
+            Show::get(&session, show_id)
+                .map_err(|err| ScrobbleError::new(format!("{:?}", err)))
+                .map(move |show| {
+                    let show_name = show.name.clone();
+                    (Scrobble::new(&show_name, &episode_name, &show_name), duration)
+                })
+        }).boxed()
+}
 
 #[derive(Clone, Debug)]
 pub struct ScrobblerConfig {
@@ -14,6 +374,7 @@ pub struct ScrobblerConfig {
     pub api_secret: String,
     pub username: String,
     pub password: String,
+    pub scrobble_podcasts: bool,
 }
 
 pub struct Scrobbler {
@@ -21,16 +382,24 @@ pub struct Scrobbler {
     scrobbler: rustfm_scrobble::Scrobbler,
 
     session: Box<Session>,
+    cache: ScrobbleCache,
+    meta_cache: SharedMetadataCache,
     current_track_id: Option<SpotifyId>,
     current_track_start: Option<Instant>,
+    current_track_start_unix: Option<u64>,
+    current_track_duration: Option<Duration>,
+    current_track_paused: Duration,
     current_track_meta: Option<Scrobble>,
     current_track_scrobbled: bool,
 
     auth_future: BoxFuture<(), rustfm_scrobble::ScrobblerError>,
     new_track_future: BoxFuture<(), ()>,
     now_playing_future: BoxFuture<(), ScrobbleError>,
-    meta_fetch_future: BoxFuture<Scrobble, ScrobbleError>,
-    scrobble_future: Option<BoxFuture<(), ScrobbleError>>
+    meta_fetch_future: BoxFuture<(Scrobble, Duration), ScrobbleError>,
+    meta_fetch_attempts: u32,
+    meta_retry_future: BoxFuture<(), ()>,
+    scrobble_future: Option<BoxFuture<(), ScrobbleError>>,
+    pending_scrobble: Option<(Scrobble, u64)>
 }
 
 #[derive(Debug)]
@@ -46,6 +415,83 @@ impl ScrobbleError {
         }
     }
 
+    /// Whether this failure was caused by an invalid/expired Last.fm session.
+    pub fn is_auth(&self) -> bool {
+        is_auth_str(&self.msg)
+    }
+
+}
+
+/// A single scrobble persisted to the offline queue, tagged with the Unix
+/// timestamp of when the track started playing so it can be re-submitted with
+/// an accurate `played_at` once connectivity returns.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedScrobble {
+    artist: String,
+    track: String,
+    album: String,
+    timestamp: u64,
+}
+
+impl CachedScrobble {
+    fn from_scrobble(scrobble: &Scrobble, timestamp: u64) -> CachedScrobble {
+        CachedScrobble {
+            artist: scrobble.artist().to_owned(),
+            track: scrobble.track().to_owned(),
+            album: scrobble.album().to_owned(),
+            timestamp: timestamp,
+        }
+    }
+
+    fn as_scrobble(&self) -> Scrobble {
+        Scrobble::new(&self.artist, &self.track, &self.album).with_timestamp(self.timestamp)
+    }
+}
+
+/// Durable queue of scrobbles that could not be submitted (offline, Last.fm
+/// 5xx, not yet authenticated). Entries survive restarts by being mirrored to
+/// a JSON file as an array, each entry carrying its played-at time, and are
+/// drained in batches once the
+/// scrobbler is authenticated again.
+struct ScrobbleCache {
+    path: PathBuf,
+    entries: Vec<CachedScrobble>,
+}
+
+impl ScrobbleCache {
+    fn open(path: PathBuf) -> ScrobbleCache {
+        let entries = File::open(&path).ok().and_then(|mut file| {
+            let mut buf = String::new();
+            file.read_to_string(&mut buf).ok().map(|_| buf)
+        }).and_then(|buf| serde_json::from_str(&buf).ok())
+          .unwrap_or_else(Vec::new);
+
+        ScrobbleCache {
+            path: path,
+            entries: entries,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn push(&mut self, scrobble: &Scrobble, timestamp: u64) {
+        self.entries.push(CachedScrobble::from_scrobble(scrobble, timestamp));
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        match serde_json::to_string(&self.entries) {
+            Ok(data) => {
+                match File::create(&self.path).and_then(|mut file| file.write_all(data.as_bytes())) {
+                    Ok(_) => {},
+                    Err(err) => error!("Failed to persist scrobble cache: {:?}", err),
+                }
+            },
+            Err(err) => error!("Failed to serialise scrobble cache: {:?}", err),
+        }
+    }
 }
 
 impl Scrobbler {
@@ -54,15 +500,23 @@ impl Scrobbler {
         let mut scrobbler = Scrobbler {
             session: Box::new(session),
             scrobbler: rustfm_scrobble::Scrobbler::new(&config.api_key, &config.api_secret),
+            cache: ScrobbleCache::open(PathBuf::from(SCROBBLE_CACHE_PATH)),
+            meta_cache: Arc::new(Mutex::new(MetadataCache::new(METADATA_CACHE_CAPACITY))),
             current_track_id: None,
             current_track_start: None,
+            current_track_start_unix: None,
+            current_track_duration: None,
+            current_track_paused: Duration::new(0, 0),
             current_track_meta: None,
             current_track_scrobbled: false,
             auth_future: future::empty().boxed(),
             new_track_future: future::empty().boxed(),
             now_playing_future: future::empty().boxed(),
             meta_fetch_future: future::empty().boxed(),
+            meta_fetch_attempts: 0,
+            meta_retry_future: future::empty().boxed(),
             scrobble_future: None,
+            pending_scrobble: None,
             config: config
         };
 
@@ -82,6 +536,11 @@ impl Scrobbler {
     }
 
     pub fn update_current_track(&mut self, track_id: SpotifyId, force_new_track: bool) {
+        if track_id.audio_type == SpotifyAudioType::Podcast && !self.config.scrobble_podcasts {
+            info!("Ignoring podcast episode; podcast scrobbling is disabled");
+            return
+        }
+
         if !force_new_track {
             let mut new_track_detected = false;
             match self.current_track_id {
@@ -110,46 +569,63 @@ impl Scrobbler {
     pub fn set_new_track(&mut self, track_id: SpotifyId) -> BoxFuture<(), ()> {
         self.current_track_id = Some(track_id);
         self.current_track_start = Some(Instant::now());
+        self.current_track_start_unix = Some(unix_now());
+        self.current_track_duration = None;
+        self.current_track_paused = Duration::new(0, 0);
         self.current_track_meta = None;
         self.current_track_scrobbled = false;
+        self.meta_fetch_attempts = 0;
+        self.meta_retry_future = future::empty().boxed();
 
         future::ok(()).boxed()
     }
 
-    pub fn get_track_meta(&mut self, track_id: SpotifyId) -> BoxFuture<Scrobble, ScrobbleError> {
-        let session = self.session.clone();
+    /// Record a paused interval for the current track so that eligibility is
+    /// computed against actual listening time rather than wall-clock time
+    /// since the track started.
+    pub fn add_paused_interval(&mut self, paused: Duration) {
+        self.current_track_paused = self.current_track_paused + paused;
+    }
 
-        Track::get(&session, track_id).and_then(move |track| {
-            let track_name = track.clone().name;
-            let artist = *track.artists.first().expect("No artists");
-            Artist::get(&session, artist).map(move |artist| (track_name, artist.name.clone(), track, session))
-        }).and_then(move |(track_name, artist_name, track_meta, session)| {
-            Album::get(&session, track_meta.album).map(|album| (track_name, artist_name, album.name.clone()))
-        }).map_err(move |err| {
-            ScrobbleError::new(format!("{:?}", err).to_owned())
-        }).and_then(move |(track, artist, album)| {
-            future::ok(Scrobble::new(&artist, &track, &album))
-        }).boxed()
+    pub fn get_track_meta(&mut self, track_id: SpotifyId) -> BoxFuture<(Scrobble, Duration), ScrobbleError> {
+        let session = (*self.session).clone();
+        let meta_cache = self.meta_cache.clone();
+
+        // Retry transient throttling from the Spotify metadata endpoints rather
+        // than turning it into a fatal error.
+        retry_backoff(move || fetch_track_meta(session.clone(), meta_cache.clone(), track_id))
     }
 
     pub fn send_now_playing(&self, track: &Scrobble) -> BoxFuture<(), ScrobbleError> {
         info!("Now-playing scrobble: {:?}", track);
 
-        match self.scrobbler.now_playing(track) {
-            Ok(_) => future::ok(()),
-            Err(err) => future::err(ScrobbleError::new(format!("{:?}", err)))
-        }.boxed()
+        let mut scrobbler = self.scrobbler.clone();
+        let track = track.clone();
+        retry_backoff(move || {
+            match scrobbler.now_playing(&track) {
+                Ok(_) => future::ok(()),
+                Err(err) => future::err(ScrobbleError::new(format!("{:?}", err)))
+            }.boxed()
+        })
     }
 
     pub fn start_scrobble(&mut self) {
-        self.scrobble_future = match self.current_track_meta {
-            Some(ref meta) => {
-                let scrobble = &meta.clone();
-                Some(self.send_scrobble(scrobble))
+        let meta = self.current_track_meta.clone();
+        match meta {
+            Some(meta) => {
+                // Capture the scrobble and its played-at timestamp now, while
+                // the current-track fields still describe this track. By the
+                // time the submission fails `update_current_track` may have
+                // moved on to the next track and reset them, so caching from
+                // the live fields would lose or mislabel the listen.
+                let timestamp = self.current_track_start_unix.unwrap_or_else(unix_now);
+                self.scrobble_future = Some(self.send_scrobble(&meta));
+                self.pending_scrobble = Some((meta, timestamp));
             },
             None => {
                 error!("No track meta-data available for scrobble");
-                None
+                self.scrobble_future = None;
+                self.pending_scrobble = None;
             }
         }
     }
@@ -157,10 +633,50 @@ impl Scrobbler {
     pub fn send_scrobble(&self, scrobble: &Scrobble) -> BoxFuture<(), ScrobbleError> {
         info!("Scrobbling: {:?}", scrobble);
 
-        match self.scrobbler.scrobble(scrobble) {
-            Ok(_) => future::ok(()),
-            Err(err) => future::err(ScrobbleError::new(format!("{:?}", err)))
-        }.boxed()
+        let mut scrobbler = self.scrobbler.clone();
+        let scrobble = scrobble.clone();
+        retry_backoff(move || {
+            match scrobbler.scrobble(&scrobble) {
+                Ok(_) => future::ok(()),
+                Err(err) => future::err(ScrobbleError::new(format!("{:?}", err)))
+            }.boxed()
+        })
+    }
+
+    /// Drain the offline scrobble queue to Last.fm in batches of up to 50,
+    /// keeping any entries whose batch could not be submitted so they are
+    /// retried on a later drain.
+    pub fn drain_cache(&mut self) {
+        if self.cache.is_empty() {
+            return
+        }
+
+        info!("Draining {} cached scrobble(s) to Last.fm", self.cache.entries.len());
+
+        let mut remaining = Vec::new();
+        for chunk in self.cache.entries.chunks(SCROBBLE_BATCH_SIZE) {
+            let batch: Vec<Scrobble> = chunk.iter().map(CachedScrobble::as_scrobble).collect();
+            match self.scrobbler.scrobble_batch(&ScrobbleBatch::from(batch)) {
+                Ok(resp) => {
+                    // Last.fm reports acceptance per track; keep only the ones
+                    // it rejected (or never reported on) so an ignored entry
+                    // isn't silently dropped.
+                    let accepted: Vec<bool> = resp.scrobbles.iter().map(batch_entry_accepted).collect();
+                    let mut rejected = retain_unaccepted(chunk, &accepted);
+                    if !rejected.is_empty() {
+                        warn!("Last.fm ignored {} cached scrobble(s)", rejected.len());
+                    }
+                    remaining.append(&mut rejected);
+                },
+                Err(err) => {
+                    error!("Failed to submit cached scrobble batch: {:?}", err);
+                    remaining.extend_from_slice(chunk);
+                }
+            }
+        }
+
+        self.cache.entries = remaining;
+        self.cache.flush();
     }
 
     fn can_scrobble_track(&self) -> bool {
@@ -175,18 +691,23 @@ impl Scrobbler {
             None => {}
         }
 
-        match self.current_track_start {
-            Some(start_time) => {
-                let play_time = start_time.elapsed();
-                
-                if play_time > Duration::new(20, 0) {
-                    return true
-                }
+        let start_time = match self.current_track_start {
+            Some(start_time) => start_time,
+            None => return false,
+        };
 
-                false
-            },
-            _ => false
-        }
+        // Eligibility needs the track duration, which only becomes available
+        // once the metadata fetch completes.
+        let duration = match self.current_track_duration {
+            Some(duration) => duration,
+            None => return false,
+        };
+
+        // Discount time spent paused so eligibility reflects actual listening.
+        let play_time = start_time.elapsed().checked_sub(self.current_track_paused)
+            .unwrap_or(Duration::new(0, 0));
+
+        track_eligible(duration, play_time)
     }
 
 }
@@ -201,6 +722,7 @@ impl Future for Scrobbler {
             Ok(Async::Ready(_)) => {
                 info!("Authenticated with Last.fm");
                 self.auth_future = future::empty().boxed();
+                self.drain_cache();
             },
             Ok(Async::NotReady) => {
             },
@@ -215,6 +737,8 @@ impl Future for Scrobbler {
         }
 
         let mut track_scrobbled = false;
+        let mut scrobble_failed = false;
+        let mut scrobble_auth_error = false;
         match self.scrobble_future {
             Some(ref mut scrobble_future) => {
                 match scrobble_future.poll() {
@@ -222,11 +746,18 @@ impl Future for Scrobbler {
                         track_scrobbled = true;
                     },
                     Ok(Async::NotReady) => {
-                        return Ok(Async::NotReady)
+                        // The scrobble may be backing off across a timer sleep;
+                        // don't stall metadata and now-playing for a freshly
+                        // started track while we wait — let the rest of poll run.
                     },
                     Err(err) => {
+                        // The error was not a rate limit, or the rate-limit
+                        // retries have been exhausted. Don't tear the daemon
+                        // down: cache the listen for a later drain, and
+                        // re-authenticate if the session key was rejected.
                         error!("Scrobbling error: {:?}", err);
-                        return Err(())
+                        scrobble_auth_error = err.is_auth();
+                        scrobble_failed = true;
                     }
                 }
             },
@@ -235,7 +766,28 @@ impl Future for Scrobbler {
 
         if track_scrobbled {
             self.scrobble_future = None;
+            self.pending_scrobble = None;
             self.current_track_scrobbled = true;
+
+            // A live scrobble succeeding means connectivity is back, so flush
+            // anything that piled up during the outage rather than waiting for
+            // a re-auth to happen to occur.
+            self.drain_cache();
+        }
+
+        if scrobble_failed {
+            self.scrobble_future = None;
+            self.current_track_scrobbled = true;
+
+            if scrobble_auth_error {
+                self.start_auth();
+            }
+
+            // Cache the scrobble captured when it was submitted, not the live
+            // current-track fields, which may already belong to a later track.
+            if let Some((scrobble, timestamp)) = self.pending_scrobble.take() {
+                self.cache.push(&scrobble, timestamp);
+            }
         }
 
         match self.new_track_future.poll() {
@@ -262,17 +814,58 @@ impl Future for Scrobbler {
         }
 
         match self.meta_fetch_future.poll() {
-            Ok(Async::Ready(ref track)) => {
+            Ok(Async::Ready(ref result)) => {
+                let (ref track, duration) = *result;
                 self.meta_fetch_future = future::empty().boxed();
+                self.meta_fetch_attempts = 0;
                 self.now_playing_future = self.send_now_playing(track);
                 self.current_track_meta = Some(track.clone());
+                self.current_track_duration = Some(duration);
             },
             Ok(Async::NotReady) => {
-                
+
             },
             Err(err) => {
+                // A metadata failure is usually transient (throttling or a
+                // flaky lookup) rather than fatal, so schedule a bounded,
+                // backed-off retry on a real timer instead of re-issuing the
+                // fetch on every poll and busy-looping the daemon.
+                //
+                // Truly rebuilding a dropped `core::session::Session` would
+                // mean reconnecting with the Spotify credentials, cache and
+                // reactor handle used to create it — none of which are
+                // available here (only a `ScrobblerConfig` of Last.fm creds and
+                // an already-connected `Session` handle are passed to `new()`),
+                // so session re-establishment is the caller's responsibility.
                 error!("Metadata fetch error: {:?}", err);
-                return Err(())
+                self.meta_fetch_future = future::empty().boxed();
+                self.meta_fetch_attempts += 1;
+
+                if self.meta_fetch_attempts <= META_FETCH_MAX_ATTEMPTS {
+                    let wait = Duration::new(META_FETCH_BACKOFF_SECS * self.meta_fetch_attempts as u64, 0);
+                    warn!("Retrying metadata fetch in {}s (retry {}/{})",
+                          wait.as_secs(), self.meta_fetch_attempts, META_FETCH_MAX_ATTEMPTS);
+                    self.meta_retry_future = sleep(wait);
+                } else {
+                    error!("Giving up on metadata for current track after {} retries",
+                           META_FETCH_MAX_ATTEMPTS);
+                }
+            }
+        }
+
+        // Re-arm the metadata fetch once the backoff timer elapses.
+        match self.meta_retry_future.poll() {
+            Ok(Async::Ready(_)) => {
+                self.meta_retry_future = future::empty().boxed();
+                if let Some(track_id) = self.current_track_id {
+                    self.meta_fetch_future = self.get_track_meta(track_id);
+                }
+            },
+            Ok(Async::NotReady) => {
+
+            },
+            Err(_) => {
+                self.meta_retry_future = future::empty().boxed();
             }
         }
 
@@ -284,8 +877,13 @@ impl Future for Scrobbler {
                 
             },
             Err(err) => {
+                // Now-playing updates are ephemeral; log and carry on, but
+                // re-authenticate if the session key was rejected.
                 error!("Now Playing error: {:?}", err);
-                return Err(())
+                if err.is_auth() {
+                    self.start_auth();
+                }
+                self.now_playing_future = future::empty().boxed();
             }
         }
 
@@ -293,3 +891,89 @@ impl Future for Scrobbler {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cached(track: &str) -> CachedScrobble {
+        CachedScrobble {
+            artist: "Artist".to_owned(),
+            track: track.to_owned(),
+            album: "Album".to_owned(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn eligibility_rejects_tracks_of_30s_or_less() {
+        assert!(!track_eligible(Duration::new(20, 0), Duration::new(20, 0)));
+        // Exactly 30s is still too short: the rule is strictly longer than 30s.
+        assert!(!track_eligible(Duration::new(30, 0), Duration::new(30, 0)));
+    }
+
+    #[test]
+    fn eligibility_uses_half_duration_below_four_minutes() {
+        // A 3-minute track is eligible at half its length (90s), not before.
+        let duration = Duration::new(180, 0);
+        assert!(!track_eligible(duration, Duration::new(89, 0)));
+        assert!(track_eligible(duration, Duration::new(90, 0)));
+    }
+
+    #[test]
+    fn eligibility_caps_threshold_at_four_minutes() {
+        // A 20-minute track is eligible after 4 minutes, well short of half.
+        let duration = Duration::new(20 * 60, 0);
+        assert!(!track_eligible(duration, Duration::new(239, 0)));
+        assert!(track_eligible(duration, Duration::new(240, 0)));
+    }
+
+    #[test]
+    fn accepted_codes_are_recognised() {
+        assert!(code_is_accepted(""));
+        assert!(code_is_accepted("0"));
+        assert!(code_is_accepted(" 0 "));
+        assert!(!code_is_accepted("1"));
+        assert!(!code_is_accepted("29"));
+    }
+
+    #[test]
+    fn retain_keeps_only_rejected_entries() {
+        let chunk = [cached("a"), cached("b"), cached("c")];
+        let remaining = retain_unaccepted(&chunk, &[true, false, true]);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].track, "b");
+    }
+
+    #[test]
+    fn retain_keeps_entries_a_short_response_omits() {
+        let chunk = [cached("a"), cached("b"), cached("c")];
+        // Last.fm only reported on the first entry; the rest stay queued.
+        let remaining = retain_unaccepted(&chunk, &[true]);
+        assert_eq!(remaining.iter().map(|c| c.track.clone()).collect::<Vec<_>>(),
+                   vec!["b".to_owned(), "c".to_owned()]);
+    }
+
+    #[test]
+    fn retry_after_is_parsed_from_the_message() {
+        assert_eq!(retry_after_str("rate limited, retry-after: 12"), Some(Duration::new(12, 0)));
+        assert_eq!(retry_after_str("please retry in 5 seconds"), Some(Duration::new(5, 0)));
+        assert_eq!(retry_after_str("no hint here"), None);
+        // A zero retry-after carries no useful delay.
+        assert_eq!(retry_after_str("retry-after: 0"), None);
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut cache: LruCache<u32, u32> = LruCache::new(2);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        // Touching key 1 makes key 2 the least-recently-used.
+        assert_eq!(cache.get(&1), Some(10));
+        cache.insert(3, 30);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&3), Some(30));
+    }
+}